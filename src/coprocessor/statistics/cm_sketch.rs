@@ -0,0 +1,230 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// FIXME: remove following later
+#![allow(dead_code)]
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use protobuf::RepeatedField;
+use tipb::analyze::{CMSketch as PbCMSketch, CMSketchRow as PbCMSketchRow};
+
+/// CmSketch is a Count-Min Sketch: a `depth x width` matrix of counters that estimates how many
+/// times a value has been seen using bounded memory instead of one counter per distinct value.
+///
+/// Each row hashes an inserted value into one of `width` counters and increments it, so a
+/// counter can be shared by several distinct values. `estimate` takes the minimum counter across
+/// all rows, which means the result can only be inflated by collisions, never reduced: the
+/// estimate is always greater than or equal to the true count.
+pub struct CmSketch {
+    depth: usize,
+    width: usize,
+    table: Vec<Vec<u32>>,
+    // a pair of independent seeds per row, combined to build that row's hash function.
+    seeds: Vec<(u64, u64)>,
+}
+
+impl CmSketch {
+    pub fn new(depth: usize, width: usize) -> CmSketch {
+        let seeds = (0..depth)
+            .map(|i| {
+                let i = i as u64;
+                (
+                    i.wrapping_mul(0x9E37_79B9_7F4A_7C15).wrapping_add(1),
+                    i.wrapping_mul(0xC2B2_AE3D_27D4_EB4F).wrapping_add(2),
+                )
+            })
+            .collect();
+        CmSketch {
+            depth: depth,
+            width: width,
+            table: vec![vec![0u32; width]; depth],
+            seeds: seeds,
+        }
+    }
+
+    // hash computes the column that `row` maps `bytes` into, by seeding two independent
+    // hashers and combining them, a cheap stand-in for `depth` fully independent hash functions.
+    fn hash(&self, row: usize, bytes: &[u8]) -> usize {
+        let (seed1, seed2) = self.seeds[row];
+        let mut h1 = DefaultHasher::new();
+        seed1.hash(&mut h1);
+        bytes.hash(&mut h1);
+        let mut h2 = DefaultHasher::new();
+        seed2.hash(&mut h2);
+        bytes.hash(&mut h2);
+        let combined = h1.finish().wrapping_add(h2.finish());
+        (combined % self.width as u64) as usize
+    }
+
+    /// insert records one occurrence of `bytes`.
+    pub fn insert(&mut self, bytes: &[u8]) {
+        for row in 0..self.depth {
+            let col = self.hash(row, bytes);
+            self.table[row][col] = self.table[row][col].saturating_add(1);
+        }
+    }
+
+    /// estimate returns an upper bound on the number of times `bytes` has been inserted.
+    pub fn estimate(&self, bytes: &[u8]) -> u32 {
+        (0..self.depth)
+            .map(|row| self.table[row][self.hash(row, bytes)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// merge combines `other`'s counters into `self` by summing each matching `(row, column)`
+    /// counter, the same way two `CmSketch`es built over disjoint rows of the same column (for
+    /// example by separate `ANALYZE` workers) are combined into one. Both sketches must share
+    /// the same `depth` and `width`, which holds whenever they were built with the same
+    /// parameters, as `Histogram::merge` requires of its two inputs.
+    pub fn merge(&mut self, other: &CmSketch) {
+        debug_assert_eq!(self.depth, other.depth);
+        debug_assert_eq!(self.width, other.width);
+        for (row, other_row) in self.table.iter_mut().zip(other.table.iter()) {
+            for (counter, other_counter) in row.iter_mut().zip(other_row.iter()) {
+                *counter = counter.saturating_add(*other_counter);
+            }
+        }
+    }
+
+    /// to_proto converts the sketch into the tipb message shipped back to TiDB and stored
+    /// alongside the histogram in the statistics table.
+    pub fn to_proto(&self) -> PbCMSketch {
+        let mut proto = PbCMSketch::new();
+        let rows = self.table
+            .iter()
+            .map(|counters| {
+                let mut row = PbCMSketchRow::new();
+                row.set_counters(counters.clone());
+                row
+            })
+            .collect();
+        proto.set_rows(RepeatedField::from_vec(rows));
+        proto
+    }
+
+    /// from_proto rebuilds a `CmSketch` from a tipb message previously produced by `to_proto`.
+    /// Returns `Err` if the message's rows do not all share the same counter count, which would
+    /// otherwise let `insert`/`estimate` index a short row out of bounds.
+    pub fn from_proto(proto: &PbCMSketch) -> Result<CmSketch, CmSketchCodecError> {
+        let depth = proto.get_rows().len();
+        let width = proto
+            .get_rows()
+            .get(0)
+            .map_or(0, |row| row.get_counters().len());
+        if proto
+            .get_rows()
+            .iter()
+            .any(|row| row.get_counters().len() != width)
+        {
+            return Err(CmSketchCodecError::InconsistentRowWidth);
+        }
+        let mut sketch = CmSketch::new(depth, width);
+        for (row, pb_row) in sketch.table.iter_mut().zip(proto.get_rows().iter()) {
+            *row = pb_row.get_counters().to_vec();
+        }
+        Ok(sketch)
+    }
+}
+
+/// Error returned by `CmSketch::from_proto` when the message cannot be trusted to build a
+/// well-formed sketch from.
+#[derive(Debug)]
+pub enum CmSketchCodecError {
+    InconsistentRowWidth,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use coprocessor::codec::datum;
+    use coprocessor::codec::datum::Datum;
+
+    #[test]
+    fn test_cm_sketch_never_underestimates() {
+        let mut sketch = CmSketch::new(5, 16);
+        let mut encoded = Vec::new();
+        for item in (0..50).map(Datum::I64) {
+            let bytes = datum::encode_value(&[item]).unwrap();
+            encoded.push(bytes);
+        }
+
+        // insert value 3 five times, everything else once.
+        for (i, bytes) in encoded.iter().enumerate() {
+            sketch.insert(bytes);
+            if i == 3 {
+                for _ in 0..4 {
+                    sketch.insert(bytes);
+                }
+            }
+        }
+
+        assert!(sketch.estimate(&encoded[3]) >= 5);
+        for bytes in &encoded {
+            if bytes != &encoded[3] {
+                assert!(sketch.estimate(bytes) >= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let bytes = datum::encode_value(&[Datum::I64(7)]).unwrap();
+
+        let mut left = CmSketch::new(4, 16);
+        left.insert(&bytes);
+        let left_estimate = left.estimate(&bytes);
+
+        let mut right = CmSketch::new(4, 16);
+        for _ in 0..3 {
+            right.insert(&bytes);
+        }
+        let right_estimate = right.estimate(&bytes);
+
+        left.merge(&right);
+        assert!(left.estimate(&bytes) >= left_estimate + right_estimate);
+    }
+
+    #[test]
+    fn test_proto_round_trip() {
+        let bytes = datum::encode_value(&[Datum::I64(7)]).unwrap();
+        let mut sketch = CmSketch::new(4, 16);
+        sketch.insert(&bytes);
+        sketch.insert(&bytes);
+
+        let proto = sketch.to_proto();
+        let decoded = CmSketch::from_proto(&proto).unwrap();
+
+        assert_eq!(decoded.depth, sketch.depth);
+        assert_eq!(decoded.width, sketch.width);
+        assert_eq!(decoded.table, sketch.table);
+    }
+
+    #[test]
+    fn test_from_proto_rejects_inconsistent_row_widths() {
+        let sketch = CmSketch::new(2, 16);
+        let mut proto = sketch.to_proto();
+        let mut rows = proto.get_rows().to_vec();
+        // Truncate one row so it no longer matches `width`, the way a corrupted or
+        // cross-version payload might.
+        rows[1].mut_counters().pop();
+        proto.set_rows(RepeatedField::from_vec(rows));
+
+        match CmSketch::from_proto(&proto) {
+            Err(CmSketchCodecError::InconsistentRowWidth) => {}
+            other => panic!("expected InconsistentRowWidth, got {:?}", other),
+        }
+    }
+}
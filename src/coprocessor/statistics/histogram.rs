@@ -14,6 +14,55 @@
 // FIXME: remove following later
 #![allow(dead_code)]
 
+use protobuf::{Message, ProtobufError, RepeatedField};
+use tipb::analyze::{Bucket as PbBucket, CMSketch as PbCMSketch, Histogram as PbHistogram};
+
+use super::cm_sketch::{CmSketch, CmSketchCodecError};
+
+/// Error returned by `Histogram::encode`/`decode`. A superset of `ProtobufError` that also
+/// covers malformed framing around the optional count-min sketch payload `encode` appends
+/// after the tipb-compatible histogram bytes, and a malformed sketch payload itself.
+#[derive(Debug)]
+pub enum HistogramCodecError {
+    Protobuf(ProtobufError),
+    Truncated,
+    MalformedCmSketch(CmSketchCodecError),
+}
+
+impl From<ProtobufError> for HistogramCodecError {
+    fn from(err: ProtobufError) -> HistogramCodecError {
+        HistogramCodecError::Protobuf(err)
+    }
+}
+
+impl From<CmSketchCodecError> for HistogramCodecError {
+    fn from(err: CmSketchCodecError) -> HistogramCodecError {
+        HistogramCodecError::MalformedCmSketch(err)
+    }
+}
+
+// read_length_prefixed reads a `u32`-big-endian-length-prefixed byte slice out of `bytes`
+// starting at `*offset`, advancing `*offset` past it, used to frame the optional count-min
+// sketch payload `Histogram::encode` appends after the tipb histogram bytes.
+fn read_length_prefixed<'a>(
+    bytes: &'a [u8],
+    offset: &mut usize,
+) -> Result<&'a [u8], HistogramCodecError> {
+    if *offset + 4 > bytes.len() {
+        return Err(HistogramCodecError::Truncated);
+    }
+    let mut len_bytes = [0u8; 4];
+    len_bytes.copy_from_slice(&bytes[*offset..*offset + 4]);
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    *offset += 4;
+    if *offset + len > bytes.len() {
+        return Err(HistogramCodecError::Truncated);
+    }
+    let slice = &bytes[*offset..*offset + len];
+    *offset += len;
+    Ok(slice)
+}
+
 /// Bucket is an element of histogram.
 ///
 /// A bucket count is the number of items stored in all previous buckets and the current bucket.
@@ -65,6 +114,9 @@ pub struct Histogram {
     per_bucket_limit: i64,
     // max number of buckets
     buckets_num: usize,
+    // companion count-min sketch, built alongside the histogram when the caller wants
+    // hot-value frequency estimates that a bucket's single `repeats` value cannot capture.
+    cm_sketch: Option<CmSketch>,
 }
 
 impl Histogram {
@@ -76,7 +128,28 @@ impl Histogram {
         h
     }
 
-    fn iterate(&mut self, data: Vec<u8>) {
+    /// new_with_cm_sketch is like `new`, but also builds a `depth x width` count-min sketch
+    /// alongside the histogram so that `equal_row_count` can prefer its frequency estimate for
+    /// skewed data instead of assuming values are spread evenly within a bucket.
+    pub fn new_with_cm_sketch(
+        id: i64,
+        buckets_num: usize,
+        depth: usize,
+        width: usize,
+    ) -> Histogram {
+        let mut h = Histogram::new(id, buckets_num);
+        h.cm_sketch = Some(CmSketch::new(depth, width));
+        h
+    }
+
+    pub fn cm_sketch(&self) -> Option<&CmSketch> {
+        self.cm_sketch.as_ref()
+    }
+
+    pub(crate) fn iterate(&mut self, data: Vec<u8>) {
+        if let Some(ref mut sketch) = self.cm_sketch {
+            sketch.insert(&data);
+        }
         if let Some(bucket) = self.buckets.last_mut() {
             // The new item has the same value as last bucket value, to ensure that
             // a same value only stored in a single bucket, we do not increase bucket
@@ -139,6 +212,386 @@ impl Histogram {
         };
         self.per_bucket_limit *= 2;
     }
+
+    // total_row_count returns the number of rows collected into the histogram.
+    pub(crate) fn total_row_count(&self) -> f64 {
+        self.buckets.last().map_or(0.0, |b| b.count as f64)
+    }
+
+    // bucket_row_count returns the number of rows that fall into the bucket at `idx` alone,
+    // i.e. excluding the cumulative count of previous buckets.
+    fn bucket_row_count(&self, idx: usize) -> f64 {
+        if idx == 0 {
+            self.buckets[0].count as f64
+        } else {
+            (self.buckets[idx].count - self.buckets[idx - 1].count) as f64
+        }
+    }
+
+    // preceding_row_count returns the cumulative row count of every bucket strictly before `idx`.
+    fn preceding_row_count(&self, idx: usize) -> f64 {
+        if idx == 0 {
+            0.0
+        } else {
+            self.buckets[idx - 1].count as f64
+        }
+    }
+
+    // ndv_per_bucket approximates the number of distinct values stored in a single bucket,
+    // used to split equality estimates for values that do not land on a bucket boundary.
+    fn ndv_per_bucket(&self) -> f64 {
+        if self.buckets.is_empty() {
+            return 1.0;
+        }
+        (self.ndv as f64 / self.buckets.len() as f64).max(1.0)
+    }
+
+    // locate_bucket finds the bucket whose range `[lower_bound, upper_bound]` contains `value`.
+    // `Ok(idx)` means `value` equals bucket `idx`'s upper bound exactly; `Err(idx)` means
+    // `value` is below bucket `idx`'s upper bound and above the previous bucket's (inside bucket
+    // `idx`'s range or in the gap before it), or past every bucket when `idx == buckets.len()`.
+    fn locate_bucket(&self, value: &[u8]) -> Result<usize, usize> {
+        self.buckets
+            .binary_search_by(|bucket| bucket.upper_bound.as_slice().cmp(value))
+    }
+
+    /// less_row_count estimates the number of rows whose value is less than or equal to `value`.
+    pub fn less_row_count(&self, value: &[u8]) -> f64 {
+        if self.buckets.is_empty() {
+            return 0.0;
+        }
+        match self.locate_bucket(value) {
+            Ok(idx) => self.preceding_row_count(idx) + self.bucket_row_count(idx),
+            Err(idx) => {
+                if idx >= self.buckets.len() {
+                    return self.total_row_count();
+                }
+                let bucket = &self.buckets[idx];
+                let fraction = calc_fraction(&bucket.lower_bound, &bucket.upper_bound, value);
+                self.preceding_row_count(idx) + fraction * self.bucket_row_count(idx)
+            }
+        }
+    }
+
+    /// equal_row_count estimates the number of rows whose value equals `value`. When `value`
+    /// lands exactly on a bucket boundary, `repeats` is an exact observed count and is returned
+    /// as-is. Otherwise, when a companion count-min sketch is present, its estimate is used in
+    /// place of the uniform `rows / ndv` assumption below, since it captures skewed, mid-bucket
+    /// frequent values that assumption misses.
+    pub fn equal_row_count(&self, value: &[u8]) -> f64 {
+        if self.buckets.is_empty() {
+            return 0.0;
+        }
+        match self.locate_bucket(value) {
+            Ok(idx) => self.buckets[idx].repeats as f64,
+            Err(idx) => {
+                if idx >= self.buckets.len() {
+                    return 0.0;
+                }
+                if let Some(ref sketch) = self.cm_sketch {
+                    return sketch.estimate(value) as f64;
+                }
+                self.bucket_row_count(idx) / self.ndv_per_bucket()
+            }
+        }
+    }
+
+    /// between_row_count estimates the number of rows whose value lies in `(lo, hi]`.
+    pub fn between_row_count(&self, lo: &[u8], hi: &[u8]) -> f64 {
+        (self.less_row_count(hi) - self.less_row_count(lo)).max(0.0)
+    }
+
+    /// merge combines `other`, a histogram built over a disjoint set of *rows* of the same
+    /// column (for example by another `ANALYZE` worker scanning a different region), into
+    /// `self`. The two histograms' buckets commonly cover overlapping *value* ranges even
+    /// though their rows are disjoint, so buckets are first sliced against a shared set of cut
+    /// points and summed into non-overlapping elementary buckets before being collapsed down to
+    /// at most `self.buckets_num` buckets; this keeps later `less_row_count`/`equal_row_count`
+    /// queries from only ever consulting one side's contribution to an overlapping range.
+    pub fn merge(&mut self, other: Histogram) {
+        if other.buckets.is_empty() {
+            return;
+        }
+        if self.buckets.is_empty() {
+            *self = other;
+            return;
+        }
+
+        let mut items = bucket_items(&self.buckets);
+        items.extend(bucket_items(&other.buckets));
+
+        let mut cuts: Vec<Vec<u8>> = Vec::with_capacity(items.len() * 2);
+        for item in &items {
+            cuts.push(item.lower_bound.clone());
+            cuts.push(item.upper_bound.clone());
+        }
+        cuts.sort();
+        cuts.dedup();
+
+        // Slice every input bucket's (uniformly assumed) row distribution against the shared
+        // cut points and sum overlapping contributions into disjoint elementary buckets, so a
+        // query landing inside a range both inputs covered sees both inputs' rows. Each
+        // elementary bucket covers `(previous cut, cut]`; the first one ends at the smallest cut,
+        // so the rows of a single-value bucket sitting on it are kept as well.
+        let mut elementary = Vec::with_capacity(cuts.len());
+        let mut lo: Option<&Vec<u8>> = None;
+        for hi in &cuts {
+            let mut count = 0.0;
+            let mut repeats = 0;
+            for item in &items {
+                let below_lo = lo.map_or(0.0, |lo| item.rows_below(lo));
+                count += item.rows_below(hi) - below_lo;
+                if item.upper_bound == *hi {
+                    repeats += item.repeats;
+                }
+            }
+            let lower_bound = lo.unwrap_or(hi).clone();
+            lo = Some(hi);
+            let count = count.round().max(0.0) as i64;
+            if count == 0 {
+                continue;
+            }
+            elementary.push(BucketItem {
+                lower_bound: lower_bound,
+                upper_bound: hi.clone(),
+                count: count,
+                repeats: repeats,
+            });
+        }
+
+        let mut items = elementary;
+        let buckets_num = self.buckets_num;
+        while items.len() > buckets_num {
+            // Merge the adjacent pair with the smallest combined row count, which keeps the
+            // resulting buckets as close to equi-depth as the collapsing allows.
+            let mut merge_at = 0;
+            let mut merge_at_count = i64::max_value();
+            for i in 0..items.len() - 1 {
+                let combined = items[i].count + items[i + 1].count;
+                if combined < merge_at_count {
+                    merge_at_count = combined;
+                    merge_at = i;
+                }
+            }
+            let right = items.remove(merge_at + 1);
+            let left = &mut items[merge_at];
+            left.upper_bound = right.upper_bound;
+            left.count += right.count;
+            left.repeats = right.repeats;
+        }
+
+        let mut cumulative = 0;
+        self.buckets = items
+            .into_iter()
+            .map(|item| {
+                cumulative += item.count;
+                Bucket::new(cumulative, item.upper_bound, item.lower_bound, item.repeats)
+            })
+            .collect();
+        // ndv is summed directly: a value observed by both workers is double-counted, the same
+        // approximation equi-depth histograms already make once rows from different sources are
+        // combined without re-scanning for exact distinctness.
+        self.ndv = (self.ndv + other.ndv).max(0);
+        self.per_bucket_limit = self.per_bucket_limit.max(other.per_bucket_limit);
+
+        if let Some(other_sketch) = other.cm_sketch {
+            match self.cm_sketch {
+                Some(ref mut sketch) => sketch.merge(&other_sketch),
+                None => self.cm_sketch = Some(other_sketch),
+            }
+        }
+    }
+
+    /// scale_counts multiplies every bucket's cumulative row count and `repeats` by `scale`.
+    /// It is used to project a histogram built from a bounded sample (see `SampleCollector`)
+    /// back up to an estimate of the full column's row counts.
+    pub(crate) fn scale_counts(&mut self, scale: f64) {
+        for bucket in &mut self.buckets {
+            bucket.count = (bucket.count as f64 * scale).round() as i64;
+            bucket.repeats = (bucket.repeats as f64 * scale).round() as i64;
+        }
+    }
+
+    /// to_proto converts the histogram into the tipb message shipped back to TiDB and stored
+    /// in the statistics table. `id`, `per_bucket_limit` and `buckets_num` are not part of the
+    /// wire format and must be carried alongside it by the caller; use `from_proto` to restore
+    /// a full `Histogram` from both.
+    pub fn to_proto(&self) -> PbHistogram {
+        let mut proto = PbHistogram::new();
+        proto.set_ndv(self.ndv);
+        let buckets = self.buckets
+            .iter()
+            .map(|b| {
+                let mut bucket = PbBucket::new();
+                bucket.set_count(b.count);
+                bucket.set_lower_bound(b.lower_bound.clone());
+                bucket.set_upper_bound(b.upper_bound.clone());
+                bucket.set_repeats(b.repeats);
+                bucket
+            })
+            .collect();
+        proto.set_buckets(RepeatedField::from_vec(buckets));
+        proto
+    }
+
+    /// from_proto rebuilds a `Histogram` from a tipb message previously produced by `to_proto`,
+    /// given back the `id`, `per_bucket_limit` and `buckets_num` the wire format does not carry.
+    pub fn from_proto(
+        id: i64,
+        per_bucket_limit: i64,
+        buckets_num: usize,
+        mut proto: PbHistogram,
+    ) -> Histogram {
+        let mut h = Histogram::new(id, buckets_num);
+        h.per_bucket_limit = per_bucket_limit;
+        h.ndv = proto.get_ndv();
+        h.buckets = proto
+            .take_buckets()
+            .into_iter()
+            .map(|mut bucket| {
+                Bucket::new(
+                    bucket.get_count(),
+                    bucket.take_upper_bound(),
+                    bucket.take_lower_bound(),
+                    bucket.get_repeats(),
+                )
+            })
+            .collect();
+        h
+    }
+
+    /// encode serializes the histogram's `ndv` and buckets, and the companion count-min sketch,
+    /// if any, so it can be persisted to the statistics table or sent across an RPC. `id`,
+    /// `per_bucket_limit` and `buckets_num` are not written: `decode`, like `from_proto`, takes
+    /// them from the caller. The tipb message produced by `to_proto` has no field for the
+    /// sketch, so it is appended as a separate length-prefixed tipb `CMSketch` message after
+    /// the histogram's.
+    pub fn encode(&self) -> Result<Vec<u8>, HistogramCodecError> {
+        let hist_bytes = self.to_proto().write_to_bytes()?;
+        let mut out = Vec::with_capacity(hist_bytes.len() + 9);
+        out.extend_from_slice(&(hist_bytes.len() as u32).to_be_bytes());
+        out.extend_from_slice(&hist_bytes);
+        match self.cm_sketch {
+            Some(ref sketch) => {
+                let sketch_bytes = sketch.to_proto().write_to_bytes()?;
+                out.push(1);
+                out.extend_from_slice(&(sketch_bytes.len() as u32).to_be_bytes());
+                out.extend_from_slice(&sketch_bytes);
+            }
+            None => out.push(0),
+        }
+        Ok(out)
+    }
+
+    /// decode is the inverse of `encode`.
+    pub fn decode(
+        id: i64,
+        per_bucket_limit: i64,
+        buckets_num: usize,
+        bytes: &[u8],
+    ) -> Result<Histogram, HistogramCodecError> {
+        let mut offset = 0;
+        let hist_bytes = read_length_prefixed(bytes, &mut offset)?;
+        let mut proto = PbHistogram::new();
+        proto.merge_from_bytes(hist_bytes)?;
+        let mut h = Histogram::from_proto(id, per_bucket_limit, buckets_num, proto);
+
+        let has_sketch = *bytes.get(offset).ok_or(HistogramCodecError::Truncated)?;
+        offset += 1;
+        if has_sketch == 1 {
+            let sketch_bytes = read_length_prefixed(bytes, &mut offset)?;
+            let mut sketch_proto = PbCMSketch::new();
+            sketch_proto.merge_from_bytes(sketch_bytes)?;
+            h.cm_sketch = Some(CmSketch::from_proto(&sketch_proto)?);
+        }
+        Ok(h)
+    }
+}
+
+// BucketItem is a bucket detached from its histogram's running cumulative count, used as the
+// unit of work while merging two histograms' buckets together.
+struct BucketItem {
+    lower_bound: Vec<u8>,
+    upper_bound: Vec<u8>,
+    count: i64,
+    repeats: i64,
+}
+
+impl BucketItem {
+    // rows_below estimates how many of this bucket's rows have a value less than or equal to
+    // `value`, assuming rows are spread uniformly across `[lower_bound, upper_bound]`. It is
+    // used to slice a bucket's contribution at arbitrary cut points when merging two
+    // histograms whose buckets may cover overlapping value ranges.
+    fn rows_below(&self, value: &[u8]) -> f64 {
+        if value >= self.upper_bound.as_slice() {
+            self.count as f64
+        } else if value <= self.lower_bound.as_slice() {
+            0.0
+        } else {
+            calc_fraction(&self.lower_bound, &self.upper_bound, value) * self.count as f64
+        }
+    }
+}
+
+// bucket_items converts a histogram's cumulative-count buckets into standalone items, each
+// carrying its own row count rather than the running total.
+fn bucket_items(buckets: &[Bucket]) -> Vec<BucketItem> {
+    buckets
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            let count = if i == 0 {
+                b.count
+            } else {
+                b.count - buckets[i - 1].count
+            };
+            BucketItem {
+                lower_bound: b.lower_bound.clone(),
+                upper_bound: b.upper_bound.clone(),
+                count: count,
+                repeats: b.repeats,
+            }
+        })
+        .collect()
+}
+
+// common_prefix_len returns the length of the longest shared prefix of `a` and `b`.
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|&(x, y)| x == y).count()
+}
+
+// bytes_to_u64 reads up to the first 8 bytes of `bytes` as a big-endian integer, treating a
+// shorter slice as if it were zero-padded on the right. This gives a monotonic numeric proxy
+// for datum-encoded byte strings that agrees with their lexicographic order.
+fn bytes_to_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    let n = bytes.len().min(8);
+    buf[..n].copy_from_slice(&bytes[..n]);
+    u64::from_be_bytes(buf)
+}
+
+// calc_fraction estimates where `value` falls between `lower` and `upper`, as a number in
+// `[0, 1]`, by linearly interpolating the encoded byte values past their common prefix.
+fn calc_fraction(lower: &[u8], upper: &[u8], value: &[u8]) -> f64 {
+    if lower >= upper {
+        return 0.5;
+    }
+    if value <= lower {
+        return 0.0;
+    }
+    if value >= upper {
+        return 1.0;
+    }
+    let prefix = common_prefix_len(lower, upper);
+    let lower_num = bytes_to_u64(&lower[prefix..]);
+    let upper_num = bytes_to_u64(&upper[prefix..]);
+    let value_num = bytes_to_u64(&value[prefix.min(value.len())..]);
+    if upper_num == lower_num {
+        return 0.5;
+    }
+    ((value_num.saturating_sub(lower_num)) as f64 / (upper_num - lower_num) as f64)
+        .min(1.0)
+        .max(0.0)
 }
 
 
@@ -209,4 +662,223 @@ mod test {
         assert_eq!(hist.buckets.len(), 3);
         assert_eq!(hist.ndv, 6);
     }
+
+    #[test]
+    fn test_row_count_estimation() {
+        let buckets_num = 4;
+        let mut hist = Histogram::new(1, buckets_num);
+        // values 0..=9, each once, grouped by iterate()'s merging into 3 buckets: [0, 3], [4, 7]
+        // and [8, 9]. Values are encoded with `encode_key`, whose byte order matches numeric
+        // order for negative values too.
+        for item in (0..10).map(Datum::I64) {
+            let bytes = datum::encode_key(&[item]).unwrap();
+            hist.iterate(bytes);
+        }
+        assert_eq!(hist.ndv, 10);
+        assert_eq!(hist.total_row_count(), 10.0);
+
+        let encode = |v: i64| datum::encode_key(&[Datum::I64(v)]).unwrap();
+
+        // the smallest value has nothing below it.
+        assert_eq!(hist.less_row_count(&encode(-1)), 0.0);
+        // every value observed is <= the largest one.
+        assert_eq!(hist.less_row_count(&encode(9)), 10.0);
+        // less_row_count is monotonically non-decreasing.
+        let mut prev = 0.0;
+        for v in 0..10 {
+            let cur = hist.less_row_count(&encode(v));
+            assert!(cur >= prev);
+            prev = cur;
+        }
+
+        // an observed value gets a nonzero estimate.
+        assert!(hist.equal_row_count(&encode(3)) > 0.0);
+        // a value entirely out of range has no matches.
+        assert_eq!(hist.equal_row_count(&encode(100)), 0.0);
+
+        assert_eq!(
+            hist.between_row_count(&encode(0), &encode(9)),
+            hist.less_row_count(&encode(9)) - hist.less_row_count(&encode(0))
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        // `encode_key` keeps numeric order in byte order, so -1 sorts below 0.
+        let encode = |v: i64| datum::encode_key(&[Datum::I64(v)]).unwrap();
+
+        let mut left = Histogram::new(1, 4);
+        for item in (0..5).map(Datum::I64) {
+            left.iterate(datum::encode_key(&[item]).unwrap());
+        }
+
+        let mut right = Histogram::new(1, 4);
+        for item in (5..10).map(Datum::I64) {
+            right.iterate(datum::encode_key(&[item]).unwrap());
+        }
+
+        let total_ndv = left.ndv + right.ndv;
+        left.merge(right);
+
+        assert!(left.buckets.len() <= 4);
+        assert_eq!(left.total_row_count(), 10.0);
+        assert_eq!(left.ndv, total_ndv);
+        assert_eq!(left.less_row_count(&encode(9)), 10.0);
+        assert_eq!(left.less_row_count(&encode(-1)), 0.0);
+    }
+
+    #[test]
+    fn test_merge_overlapping_ranges() {
+        let encode = |v: i64| datum::encode_value(&[Datum::I64(v)]).unwrap();
+
+        // `self`'s bucket [10, 30] (20 rows) and `other`'s bucket [5, 20] (20 rows): disjoint
+        // rows, but overlapping value ranges in [10, 20], the scenario flagged in review where
+        // a naive sort-and-collapse merge silently drops one side's rows from the overlap.
+        let mut left = Histogram::new(1, 4);
+        left.buckets.push(Bucket::new(20, encode(30), encode(10), 1));
+        left.ndv = 20;
+
+        let mut right = Histogram::new(1, 4);
+        right.buckets.push(Bucket::new(20, encode(20), encode(5), 1));
+        right.ndv = 20;
+
+        let query = encode(15);
+        let left_alone = left.less_row_count(&query);
+        let right_alone = right.less_row_count(&query);
+
+        left.merge(right);
+
+        assert_eq!(left.total_row_count(), 40.0);
+        // 15 sits strictly inside both input ranges, so the merged estimate must include rows
+        // from both sides rather than only whichever single bucket a binary search lands on.
+        assert!(left.less_row_count(&query) > left_alone);
+        assert!(left.less_row_count(&query) > right_alone);
+    }
+
+    #[test]
+    fn test_merge_combines_cm_sketches() {
+        let encode = |v: i64| datum::encode_value(&[Datum::I64(v)]).unwrap();
+
+        let mut left = Histogram::new_with_cm_sketch(1, 4, 4, 16);
+        for item in (0..5).map(Datum::I64) {
+            left.iterate(datum::encode_value(&[item]).unwrap());
+        }
+        let mut right = Histogram::new_with_cm_sketch(1, 4, 4, 16);
+        for item in (5..10).map(Datum::I64) {
+            right.iterate(datum::encode_value(&[item]).unwrap());
+        }
+
+        let query = encode(7);
+        // For any two CM sketch rows a_i, b_i: a_i + b_i >= min(a) + min(b), so this sum is a
+        // safe lower bound on what the merged sketch must report.
+        let expected = left.cm_sketch().unwrap().estimate(&query)
+            + right.cm_sketch().unwrap().estimate(&query);
+
+        left.merge(right);
+        assert!(left.cm_sketch().unwrap().estimate(&query) >= expected);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let buckets_num = 3;
+        let mut hist = Histogram::new(42, buckets_num);
+        for item in (0..6).map(Datum::I64) {
+            hist.iterate(datum::encode_value(&[item]).unwrap());
+        }
+
+        let bytes = hist.encode().unwrap();
+        let decoded =
+            Histogram::decode(hist.id, hist.per_bucket_limit, hist.buckets_num, &bytes).unwrap();
+
+        assert_eq!(decoded.id, hist.id);
+        assert_eq!(decoded.ndv, hist.ndv);
+        assert_eq!(decoded.per_bucket_limit, hist.per_bucket_limit);
+        assert_eq!(decoded.buckets.len(), hist.buckets.len());
+        for (a, b) in decoded.buckets.iter().zip(hist.buckets.iter()) {
+            assert_eq!(a.count, b.count);
+            assert_eq!(a.lower_bound, b.lower_bound);
+            assert_eq!(a.upper_bound, b.upper_bound);
+            assert_eq!(a.repeats, b.repeats);
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_round_trips_cm_sketch() {
+        let mut hist = Histogram::new_with_cm_sketch(7, 3, 4, 16);
+        for item in (0..6).map(Datum::I64) {
+            hist.iterate(datum::encode_value(&[item]).unwrap());
+        }
+
+        let bytes = hist.encode().unwrap();
+        let decoded =
+            Histogram::decode(hist.id, hist.per_bucket_limit, hist.buckets_num, &bytes).unwrap();
+
+        let query = datum::encode_value(&[Datum::I64(3)]).unwrap();
+        assert_eq!(
+            decoded.cm_sketch().unwrap().estimate(&query),
+            hist.cm_sketch().unwrap().estimate(&query)
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_without_cm_sketch_has_none() {
+        let mut hist = Histogram::new(42, 3);
+        for item in (0..6).map(Datum::I64) {
+            hist.iterate(datum::encode_value(&[item]).unwrap());
+        }
+
+        let bytes = hist.encode().unwrap();
+        let decoded =
+            Histogram::decode(hist.id, hist.per_bucket_limit, hist.buckets_num, &bytes).unwrap();
+        assert!(decoded.cm_sketch().is_none());
+    }
+
+    #[test]
+    fn test_equal_row_count_prefers_cm_sketch_mid_bucket() {
+        // The hot value must lie strictly inside a bucket, not on its upper bound, for
+        // `equal_row_count` to take its `Err(idx)` branch.
+        let mut hist = Histogram::new_with_cm_sketch(1, 1, 5, 16);
+        assert!(hist.cm_sketch().is_some());
+
+        for item in (0..3).map(Datum::I64) {
+            hist.iterate(datum::encode_value(&[item]).unwrap());
+        }
+        // 3 is a hot value: it shows up far more often than its bucket's uniform share.
+        for _ in 0..22 {
+            hist.iterate(datum::encode_value(&[Datum::I64(3)]).unwrap());
+        }
+        for item in (4..10).map(Datum::I64) {
+            hist.iterate(datum::encode_value(&[item]).unwrap());
+        }
+
+        let encode = |v: i64| datum::encode_value(&[Datum::I64(v)]).unwrap();
+        // `iterate` closes a bucket right after the run of 3s, leaving 3 as the first bucket's
+        // upper bound; replace the buckets with one bucket [0, 9] holding all 31 rows so that 3
+        // lies strictly inside it. The sketch built by `iterate` is kept.
+        hist.buckets = vec![Bucket::new(31, encode(9), encode(0), 1)];
+        assert!(hist.equal_row_count(&encode(3)) >= 22.0);
+    }
+
+    #[test]
+    fn test_equal_row_count_keeps_exact_repeats_on_boundary() {
+        let encode = |v: i64| datum::encode_value(&[Datum::I64(v)]).unwrap();
+        let mut hist = Histogram::new_with_cm_sketch(1, 1, 5, 16);
+        for item in (0..5).map(Datum::I64) {
+            hist.iterate(encode(item));
+        }
+        // With a single bucket, its upper bound is always the last (largest) value inserted,
+        // and `repeats` is the exact observed count for that boundary value.
+        let exact_repeats = hist.buckets[0].repeats;
+        assert_eq!(hist.buckets[0].upper_bound, encode(4));
+        assert_eq!(exact_repeats, 1);
+
+        // Inflate the sketch's estimate for the boundary value far past the true repeats via
+        // unrelated inserts, to prove the exact boundary case never substitutes it in.
+        for item in (100..300).map(Datum::I64) {
+            hist.cm_sketch.as_mut().unwrap().insert(&encode(item));
+        }
+        assert!(hist.cm_sketch().unwrap().estimate(&encode(4)) > exact_repeats as u32);
+
+        assert_eq!(hist.equal_row_count(&encode(4)), exact_repeats as f64);
+    }
 }
@@ -0,0 +1,160 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// FIXME: remove following later
+#![allow(dead_code)]
+
+/// LogLinearHistogram is an alternate, non-merging bucketing mode for numeric columns with a
+/// wide dynamic range (timestamps, sizes). Unlike `Histogram`'s equi-depth buckets, which are
+/// merged based on the data seen, `LogLinearHistogram` places values into predetermined bounds
+/// fixed by three parameters:
+///
+/// * `m`: the minimum resolution, giving linear bucket width `M = 2^m`.
+/// * `r`: the resolution-range boundary `R = 2^r`; values below `R` fall into linear buckets of
+///   width `M`.
+/// * `n`: the maximum value `N = 2^n` the histogram tracks; values are clamped to `N - 1`.
+///
+/// Values at or above `R` are handled one power-of-two band at a time: each band
+/// `[2^k, 2^(k+1))` for `k >= r` is subdivided into `R / M` equal linear sub-buckets. This
+/// guarantees a relative error bounded by `M / R` everywhere above `R`, with predictable memory
+/// use fixed by `m`, `r` and `n` rather than by the data.
+pub struct LogLinearHistogram {
+    m: u32,
+    r: u32,
+    n: u32,
+    // number of linear buckets spanning [0, R), and also the number of sub-buckets per band.
+    linear_buckets: usize,
+    counts: Vec<u64>,
+}
+
+impl LogLinearHistogram {
+    pub fn new(m: u32, r: u32, n: u32) -> LogLinearHistogram {
+        assert!(r >= m, "resolution-range boundary must be >= minimum resolution");
+        assert!(n > r, "maximum must be greater than the resolution-range boundary");
+        assert!(n < 64, "maximum exponent must be less than 64 to fit in a u64 shift");
+
+        let linear_buckets = 1usize << (r - m);
+        let log_bands = (n - r) as usize;
+        let total_buckets = linear_buckets + log_bands * linear_buckets;
+        LogLinearHistogram {
+            m: m,
+            r: r,
+            n: n,
+            linear_buckets: linear_buckets,
+            counts: vec![0u64; total_buckets],
+        }
+    }
+
+    // bucket_index maps `value` to the slot in `counts` that holds its bucket.
+    fn bucket_index(&self, value: u64) -> usize {
+        let r = 1u64 << self.r;
+        let n = 1u64 << self.n;
+        let m = 1u64 << self.m;
+        let value = value.min(n - 1);
+
+        if value < r {
+            (value / m) as usize
+        } else {
+            let log2_value = 63 - value.leading_zeros();
+            let band = (log2_value - self.r) as usize;
+            let band_start = 1u64 << (self.r as usize + band);
+            let sub_bucket_width = band_start / self.linear_buckets as u64;
+            let offset = ((value - band_start) / sub_bucket_width) as usize;
+            self.linear_buckets + band * self.linear_buckets + offset
+        }
+    }
+
+    // bucket_bounds returns the `[lower, upper)` range a bucket slot covers.
+    fn bucket_bounds(&self, idx: usize) -> (u64, u64) {
+        let m = 1u64 << self.m;
+        if idx < self.linear_buckets {
+            let idx = idx as u64;
+            (idx * m, (idx + 1) * m)
+        } else {
+            let offset = idx - self.linear_buckets;
+            let band = offset / self.linear_buckets;
+            let sub_idx = (offset % self.linear_buckets) as u64;
+            let band_start = 1u64 << (self.r as usize + band);
+            let sub_bucket_width = band_start / self.linear_buckets as u64;
+            let lower = band_start + sub_idx * sub_bucket_width;
+            (lower, lower + sub_bucket_width)
+        }
+    }
+
+    /// record adds one occurrence of `value`, clamping it to `N - 1` if it is out of range.
+    pub fn record(&mut self, value: u64) {
+        let idx = self.bucket_index(value);
+        self.counts[idx] += 1;
+    }
+
+    /// buckets returns every non-empty bucket as `(lower, upper, count)`, in ascending order.
+    pub fn buckets(&self) -> Vec<(u64, u64, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(idx, &count)| {
+                let (lower, upper) = self.bucket_bounds(idx);
+                (lower, upper, count)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_linear_region() {
+        // M = 2, R = 8, N = 1024
+        let mut hist = LogLinearHistogram::new(1, 3, 10);
+        hist.record(0);
+        hist.record(1);
+        hist.record(5);
+
+        let buckets = hist.buckets();
+        // value 0 and 1 share bucket [0, 2), value 5 falls in [4, 6).
+        assert_eq!(buckets, vec![(0, 2, 2), (4, 6, 1)]);
+    }
+
+    #[test]
+    fn test_log_region_subdivides_bands() {
+        // M = 1, R = 4, N = 16: band [4, 8) is split into R/M = 4 sub-buckets of width 1.
+        let mut hist = LogLinearHistogram::new(0, 2, 4);
+        hist.record(4);
+        hist.record(7);
+
+        let buckets = hist.buckets();
+        assert_eq!(buckets, vec![(4, 5, 1), (7, 8, 1)]);
+    }
+
+    #[test]
+    fn test_out_of_range_values_are_clamped() {
+        let mut hist = LogLinearHistogram::new(0, 2, 4);
+        hist.record(1000);
+        let buckets = hist.buckets();
+        assert_eq!(buckets.len(), 1);
+        let (lower, upper, count) = buckets[0];
+        assert!(lower < 16 && upper <= 16);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "maximum exponent must be less than 64")]
+    fn test_n_must_fit_in_a_u64_shift() {
+        // N = 2^64 would overflow the `1u64 << self.n` shifts bucket_index/bucket_bounds rely
+        // on; reject it up front instead of panicking or silently wrapping inside record().
+        LogLinearHistogram::new(0, 2, 64);
+    }
+}
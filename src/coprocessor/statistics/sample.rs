@@ -0,0 +1,125 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// FIXME: remove following later
+#![allow(dead_code)]
+
+use rand::{self, Rng};
+
+use super::histogram::Histogram;
+
+/// SampleCollector builds a bounded, uniform sample of a column's datum-encoded values using
+/// Vitter's reservoir sampling (algorithm R), so `ANALYZE` can build a `Histogram` from a single
+/// streaming pass over a huge table instead of requiring the whole sorted column in memory.
+pub struct SampleCollector {
+    reservoir: Vec<Vec<u8>>,
+    max_sample_size: usize,
+    total_rows: u64,
+}
+
+impl SampleCollector {
+    pub fn new(max_sample_size: usize) -> SampleCollector {
+        SampleCollector {
+            reservoir: Vec::with_capacity(max_sample_size),
+            max_sample_size: max_sample_size,
+            total_rows: 0,
+        }
+    }
+
+    pub fn total_rows(&self) -> u64 {
+        self.total_rows
+    }
+
+    pub fn samples(&self) -> &[Vec<u8>] {
+        &self.reservoir
+    }
+
+    /// sample offers one more datum-encoded row to the reservoir. The first `max_sample_size`
+    /// rows are always kept; after that, row `i` (0-based) replaces a uniformly random slot
+    /// `j` in `[0, i]` when `j` lands inside the reservoir, and is discarded otherwise. This
+    /// keeps every row seen so far equally likely to be in the final sample.
+    pub fn sample(&mut self, data: Vec<u8>) {
+        let i = self.total_rows;
+        self.total_rows += 1;
+        if (i as usize) < self.max_sample_size {
+            self.reservoir.push(data);
+            return;
+        }
+        let j = rand::thread_rng().gen_range(0, i + 1) as usize;
+        if j < self.max_sample_size {
+            self.reservoir[j] = data;
+        }
+    }
+
+    /// into_histogram sorts the collected sample and feeds it through `Histogram::iterate`,
+    /// then scales every bucket's row count by `total_rows / sample_size` so the result
+    /// approximates the histogram a full scan of the column would have produced.
+    pub fn into_histogram(mut self, id: i64, buckets_num: usize) -> Histogram {
+        self.reservoir.sort();
+        let sample_size = self.reservoir.len();
+
+        let mut hist = Histogram::new(id, buckets_num);
+        for data in self.reservoir {
+            hist.iterate(data);
+        }
+
+        if sample_size > 0 && self.total_rows > sample_size as u64 {
+            let scale = self.total_rows as f64 / sample_size as f64;
+            hist.scale_counts(scale);
+        }
+        hist
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use coprocessor::codec::datum;
+    use coprocessor::codec::datum::Datum;
+
+    #[test]
+    fn test_sample_collector_bounded_size() {
+        let mut collector = SampleCollector::new(10);
+        for item in (0..1000).map(Datum::I64) {
+            let bytes = datum::encode_value(&[item]).unwrap();
+            collector.sample(bytes);
+        }
+        assert_eq!(collector.total_rows(), 1000);
+        assert_eq!(collector.samples().len(), 10);
+    }
+
+    #[test]
+    fn test_sample_collector_keeps_everything_below_capacity() {
+        let mut collector = SampleCollector::new(10);
+        for item in (0..5).map(Datum::I64) {
+            let bytes = datum::encode_value(&[item]).unwrap();
+            collector.sample(bytes);
+        }
+        assert_eq!(collector.total_rows(), 5);
+        assert_eq!(collector.samples().len(), 5);
+    }
+
+    #[test]
+    fn test_into_histogram_scales_counts() {
+        let mut collector = SampleCollector::new(10);
+        for item in (0..10).map(Datum::I64) {
+            let bytes = datum::encode_value(&[item]).unwrap();
+            collector.sample(bytes);
+        }
+        // Pretend the full column had 100 rows, so every sampled row stands in for 10.
+        collector.total_rows = 100;
+
+        let hist = collector.into_histogram(1, 4);
+        assert_eq!(hist.total_row_count(), 100.0);
+    }
+}